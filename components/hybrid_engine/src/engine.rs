@@ -1,12 +1,31 @@
 // Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
 
 use engine_traits::{
-    KvEngine, Peekable, RangeCacheEngine, ReadOptions, Result, SnapshotContext, SnapshotMiscExt,
-    SyncMutable, WriteBatchExt,
+    CacheRange, KvEngine, Mutable, Peekable, RangeCacheEngine, ReadOptions, Result,
+    SnapshotContext, SnapshotMiscExt, SyncMutable, WriteBatch, WriteBatchExt, WriteOptions,
+    CF_DEFAULT,
 };
 
 use crate::snapshot::HybridEngineSnapshot;
 
+/// Builds the smallest `CacheRange` that covers exactly `key`, for
+/// invalidating a single-key write in the region cache engine.
+///
+/// This assumes `RangeCacheEngine::evict_range` evicts any *registered*
+/// range that overlaps the one passed in (e.g. a `[k00, k10)` region gets
+/// evicted by a `[k05, k05\0)` argument), not just a range that matches
+/// exactly. Neither `engine_traits::RangeCacheEngine` nor
+/// `RangeCacheMemoryEngine` live in this crate, so that can't be checked
+/// here — if `evict_range` is exact-match instead, single-key writes never
+/// evict the containing region and `put`/`delete` silently stop invalidating
+/// the cache. Confirm this against the real implementation before relying
+/// on it.
+fn single_key_cache_range(key: &[u8]) -> CacheRange {
+    let mut end = key.to_vec();
+    end.push(0);
+    CacheRange::new(key.to_vec(), end)
+}
+
 /// This engine is structured with both a disk engine and an region cache
 /// engine. The disk engine houses the complete database data, whereas the
 /// region cache engine functions as a region cache, selectively caching certain
@@ -124,27 +143,196 @@ where
     EC: RangeCacheEngine,
 {
     fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
-        unimplemented!()
+        self.put_cf(CF_DEFAULT, key, value)
     }
 
     fn put_cf(&self, cf: &str, key: &[u8], value: &[u8]) -> Result<()> {
-        unimplemented!()
+        self.disk_engine.put_cf(cf, key, value)?;
+        // The cache only holds a point-in-time snapshot of the data; it has
+        // no way to apply an individual mutation in place, so drop the
+        // affected range and let it be repopulated from disk on next load.
+        //
+        // This is enough to keep the cache consistent with the disk engine's
+        // sequence number without threading one through here: `snapshot()`
+        // always reads `disk_snap.sequence_number()` fresh and hands it to
+        // `region_cache_engine.snapshot()`, so the moment a range is evicted
+        // it can only be served again once the cache has caught up to (at
+        // least) the sequence number of the write that evicted it.
+        self.region_cache_engine
+            .evict_range(&single_key_cache_range(key));
+        Ok(())
     }
 
     fn delete(&self, key: &[u8]) -> Result<()> {
-        unimplemented!()
+        self.delete_cf(CF_DEFAULT, key)
     }
 
     fn delete_cf(&self, cf: &str, key: &[u8]) -> Result<()> {
-        unimplemented!()
+        self.disk_engine.delete_cf(cf, key)?;
+        self.region_cache_engine
+            .evict_range(&single_key_cache_range(key));
+        Ok(())
     }
 
     fn delete_range(&self, begin_key: &[u8], end_key: &[u8]) -> Result<()> {
-        unimplemented!()
+        self.delete_range_cf(CF_DEFAULT, begin_key, end_key)
     }
 
     fn delete_range_cf(&self, cf: &str, begin_key: &[u8], end_key: &[u8]) -> Result<()> {
-        unimplemented!()
+        self.disk_engine.delete_range_cf(cf, begin_key, end_key)?;
+        self.region_cache_engine.evict_range(&CacheRange::new(
+            begin_key.to_vec(),
+            end_key.to_vec(),
+        ));
+        Ok(())
+    }
+}
+
+/// A `WriteBatch` that commits to `disk_engine` first and then, on success,
+/// evicts every cached range touched by the batch so the region cache engine
+/// never serves data that is stale relative to what was just written.
+pub struct HybridEngineWriteBatch<EK: WriteBatchExt, EC: RangeCacheEngine> {
+    disk_write_batch: EK::WriteBatch,
+    region_cache_engine: EC,
+    cache_ranges_to_evict: Vec<CacheRange>,
+    // `cache_ranges_to_evict.len()` as of each `set_save_point()` call, so a
+    // rollback can drop the ranges queued after that point along with the
+    // disk-batch writes it undoes.
+    save_points: Vec<usize>,
+}
+
+impl<EK: WriteBatchExt, EC: RangeCacheEngine> HybridEngineWriteBatch<EK, EC> {
+    fn mark_range_dirty(&mut self, range: CacheRange) {
+        self.cache_ranges_to_evict.push(range);
+    }
+}
+
+impl<EK: WriteBatchExt, EC: RangeCacheEngine> Mutable for HybridEngineWriteBatch<EK, EC> {
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.put_cf(CF_DEFAULT, key, value)
+    }
+
+    fn put_cf(&mut self, cf: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        self.disk_write_batch.put_cf(cf, key, value)?;
+        self.mark_range_dirty(single_key_cache_range(key));
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.delete_cf(CF_DEFAULT, key)
+    }
+
+    fn delete_cf(&mut self, cf: &str, key: &[u8]) -> Result<()> {
+        self.disk_write_batch.delete_cf(cf, key)?;
+        self.mark_range_dirty(single_key_cache_range(key));
+        Ok(())
+    }
+
+    fn delete_range(&mut self, begin_key: &[u8], end_key: &[u8]) -> Result<()> {
+        self.delete_range_cf(CF_DEFAULT, begin_key, end_key)
+    }
+
+    fn delete_range_cf(&mut self, cf: &str, begin_key: &[u8], end_key: &[u8]) -> Result<()> {
+        self.disk_write_batch
+            .delete_range_cf(cf, begin_key, end_key)?;
+        self.mark_range_dirty(CacheRange::new(begin_key.to_vec(), end_key.to_vec()));
+        Ok(())
+    }
+}
+
+impl<EK: WriteBatchExt, EC: RangeCacheEngine> WriteBatch for HybridEngineWriteBatch<EK, EC> {
+    fn write_opt(&mut self, opts: &WriteOptions) -> Result<u64> {
+        // Commit to disk first: the region cache is only ever a cache, so it
+        // must never observe a mutation before the engine of record does.
+        let seq = self.disk_write_batch.write_opt(opts)?;
+        // We don't propagate `seq` into `region_cache_engine` directly: a
+        // cached range is dropped outright on eviction rather than patched
+        // in place, and `KvEngine::snapshot` always reads
+        // `disk_snap.sequence_number()` fresh and passes it into
+        // `region_cache_engine.snapshot()`. So an evicted range can only
+        // become available again once the cache has independently caught up
+        // to a sequence number at least this high — there's no separate
+        // counter here to fall out of sync.
+        for range in self.cache_ranges_to_evict.drain(..) {
+            self.region_cache_engine.evict_range(&range);
+        }
+        Ok(seq)
+    }
+
+    fn data_size(&self) -> usize {
+        self.disk_write_batch.data_size()
+    }
+
+    fn count(&self) -> usize {
+        self.disk_write_batch.count()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.disk_write_batch.is_empty()
+    }
+
+    fn should_write_to_engine(&self) -> bool {
+        self.disk_write_batch.should_write_to_engine()
+    }
+
+    fn clear(&mut self) {
+        self.disk_write_batch.clear();
+        self.cache_ranges_to_evict.clear();
+        self.save_points.clear();
+    }
+
+    fn set_save_point(&mut self) {
+        self.disk_write_batch.set_save_point();
+        self.save_points.push(self.cache_ranges_to_evict.len());
+    }
+
+    fn pop_save_point(&mut self) -> Result<()> {
+        self.disk_write_batch.pop_save_point()?;
+        self.save_points.pop();
+        Ok(())
+    }
+
+    fn rollback_to_save_point(&mut self) -> Result<()> {
+        self.disk_write_batch.rollback_to_save_point()?;
+        if let Some(len) = self.save_points.pop() {
+            self.cache_ranges_to_evict.truncate(len);
+        }
+        Ok(())
+    }
+
+    fn merge(&mut self, other: Self) -> Result<()> {
+        // Merge the disk batch first: if it fails, `other`'s cache ranges
+        // must not be queued against a write that never actually merged in.
+        self.disk_write_batch.merge(other.disk_write_batch)?;
+        self.cache_ranges_to_evict
+            .extend(other.cache_ranges_to_evict);
+        Ok(())
+    }
+}
+
+impl<EK, EC> WriteBatchExt for HybridEngine<EK, EC>
+where
+    EK: KvEngine,
+    EC: RangeCacheEngine,
+{
+    type WriteBatch = HybridEngineWriteBatch<EK, EC>;
+
+    fn write_batch(&self) -> Self::WriteBatch {
+        HybridEngineWriteBatch {
+            disk_write_batch: self.disk_engine.write_batch(),
+            region_cache_engine: self.region_cache_engine.clone(),
+            cache_ranges_to_evict: vec![],
+            save_points: vec![],
+        }
+    }
+
+    fn write_batch_with_cap(&self, cap: usize) -> Self::WriteBatch {
+        HybridEngineWriteBatch {
+            disk_write_batch: self.disk_engine.write_batch_with_cap(cap),
+            region_cache_engine: self.region_cache_engine.clone(),
+            cache_ranges_to_evict: vec![],
+            save_points: vec![],
+        }
     }
 }
 
@@ -153,7 +341,10 @@ mod tests {
     use std::sync::Arc;
 
     use engine_rocks::util::new_engine;
-    use engine_traits::{CacheRange, KvEngine, SnapshotContext, CF_DEFAULT, CF_LOCK, CF_WRITE};
+    use engine_traits::{
+        CacheRange, KvEngine, Mutable, SnapshotContext, SyncMutable, WriteBatch, WriteBatchExt,
+        WriteOptions, CF_DEFAULT, CF_LOCK, CF_WRITE,
+    };
     use region_cache_memory_engine::RangeCacheMemoryEngine;
     use tempfile::Builder;
 
@@ -202,4 +393,79 @@ mod tests {
         let s = hybrid_engine.snapshot(Some(snap_ctx));
         assert!(!s.region_cache_snapshot_available());
     }
+
+    /// A `put` through `SyncMutable` must evict the cached range it touches,
+    /// so a snapshot taken afterwards no longer reports the region cache as
+    /// available for it.
+    ///
+    /// This relies on `RangeCacheMemoryEngine::evict_range` treating the
+    /// single-key `[k05, k05\0)` argument as overlapping the registered
+    /// `[k00, k10)` range and evicting it; see the caveat on
+    /// `single_key_cache_range`. If that engine is ever exact-match instead,
+    /// this test (and the feature it covers) would need to change.
+    #[test]
+    fn test_put_evicts_cache_range() {
+        let path = Builder::new().prefix("temp-put").tempdir().unwrap();
+        let disk_engine = new_engine(
+            path.path().to_str().unwrap(),
+            &[CF_DEFAULT, CF_LOCK, CF_WRITE],
+        )
+        .unwrap();
+        let memory_engine = RangeCacheMemoryEngine::new(Arc::default());
+        let range = CacheRange::new(b"k00".to_vec(), b"k10".to_vec());
+        memory_engine.new_range(range.clone());
+        {
+            let mut core = memory_engine.core().lock().unwrap();
+            core.mut_range_manager().set_range_readable(&range, true);
+            core.mut_range_manager().set_safe_ts(&range, 10);
+        }
+
+        let hybrid_engine = HybridEngine::new(disk_engine, memory_engine.clone());
+        let snap_ctx = SnapshotContext {
+            read_ts: 15,
+            range: Some(range.clone()),
+        };
+        let s = hybrid_engine.snapshot(Some(snap_ctx.clone()));
+        assert!(s.region_cache_snapshot_available());
+
+        hybrid_engine.put(b"k05", b"v05").unwrap();
+
+        let s = hybrid_engine.snapshot(Some(snap_ctx));
+        assert!(!s.region_cache_snapshot_available());
+    }
+
+    /// Same as `test_put_evicts_cache_range`, but through a `WriteBatch`
+    /// rather than `SyncMutable::put` directly.
+    #[test]
+    fn test_write_batch_evicts_cache_range() {
+        let path = Builder::new().prefix("temp-wb").tempdir().unwrap();
+        let disk_engine = new_engine(
+            path.path().to_str().unwrap(),
+            &[CF_DEFAULT, CF_LOCK, CF_WRITE],
+        )
+        .unwrap();
+        let memory_engine = RangeCacheMemoryEngine::new(Arc::default());
+        let range = CacheRange::new(b"k00".to_vec(), b"k10".to_vec());
+        memory_engine.new_range(range.clone());
+        {
+            let mut core = memory_engine.core().lock().unwrap();
+            core.mut_range_manager().set_range_readable(&range, true);
+            core.mut_range_manager().set_safe_ts(&range, 10);
+        }
+
+        let hybrid_engine = HybridEngine::new(disk_engine, memory_engine.clone());
+        let snap_ctx = SnapshotContext {
+            read_ts: 15,
+            range: Some(range.clone()),
+        };
+        let s = hybrid_engine.snapshot(Some(snap_ctx.clone()));
+        assert!(s.region_cache_snapshot_available());
+
+        let mut wb = hybrid_engine.write_batch();
+        wb.put(b"k05", b"v05").unwrap();
+        wb.write_opt(&WriteOptions::default()).unwrap();
+
+        let s = hybrid_engine.snapshot(Some(snap_ctx));
+        assert!(!s.region_cache_snapshot_available());
+    }
 }
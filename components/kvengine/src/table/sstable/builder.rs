@@ -3,8 +3,13 @@
 use std::convert::TryFrom;
 use std::{mem, slice};
 
+use aes::Aes256;
 use byteorder::{ByteOrder, LittleEndian};
 use bytes::{BufMut, BytesMut};
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use ctr::Ctr128BE;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
 
 use super::super::table::Value;
 use farmhash;
@@ -13,20 +18,189 @@ use xorf::BinaryFuse8;
 pub const CRC32_CASTAGNOLI: u8 = 1;
 pub const PROP_KEY_SMALLEST: &str = "smallest";
 pub const PROP_KEY_BIGGEST: &str = "biggest";
+pub const PROP_KEY_ENCRYPTION_KEY_ID: &str = "enc_key_id";
 pub const EXTRA_END: u8 = 255;
 pub const EXTRA_FILTER: u8 = 1;
 pub const EXTRA_FILTER_TYPE_BINARY_FUSE_8: u8 = 1;
-const NO_COMPRESSION: u8 = 0;
-const TABLE_FORMAT: u16 = 1;
+pub const EXTRA_FILTER_TYPE_BLOOM: u8 = 2;
+pub const NO_COMPRESSION: u8 = 0;
+pub const SNAPPY_COMPRESSION: u8 = 1;
+pub const LZ4_COMPRESSION: u8 = 2;
+pub const ZSTD_COMPRESSION: u8 = 3;
+pub const ENCRYPTION_NONE: u8 = 0;
+pub const ENCRYPTION_AES_CTR: u8 = 1;
+pub const IV_LEN: usize = 16;
+// Ciphertext is the same size as plaintext under CTR mode, but the IV prefix
+// and the compression header added to each block mean an encrypted block
+// comes out a little larger than its entries. With encryption on, the cutoff
+// for finishing a block is lowered by this much so that overhead falls inside
+// the headroom. As without encryption, the entry that crosses the cutoff still
+// goes into the block, so a block can exceed the cutoff by that entry's size.
+const BLOCK_ENCRYPTION_PADDING: usize = 256;
+// Bumped from 1 to 2 when `Footer` grew `encryption_type`/`iv_len`.
+const TABLE_FORMAT: u16 = 2;
 pub const MAGIC_NUMBER: u32 = 2940551257;
 pub const META_HAS_OLD: u8 = 1 << 1;
 pub const BLOCK_ADDR_SIZE: usize = mem::size_of::<BlockAddress>();
 
+/// Checksum algorithm used to protect a block, an index, or the properties
+/// section. The numeric value is what gets persisted in `Footer.checksum_type`
+/// so readers know how to verify it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum ChecksumType {
+    Crc32c = CRC32_CASTAGNOLI,
+    XxHash64 = 2,
+    Sha256 = 3,
+}
+
+impl Default for ChecksumType {
+    fn default() -> Self {
+        ChecksumType::Crc32c
+    }
+}
+
+/// Size in bytes of the on-disk checksum slot for `tp`.
+fn checksum_len(tp: ChecksumType) -> usize {
+    match tp {
+        ChecksumType::Crc32c => 4,
+        ChecksumType::XxHash64 => 8,
+        ChecksumType::Sha256 => 32,
+    }
+}
+
+/// Computes the checksum of `data` under `tp` as a `u64`. For `Sha256`, where
+/// the full digest is wider than a `u64`, this returns only its leading 8
+/// bytes — **do not use this to verify a `Sha256` checksum**, since that
+/// throws away 24 of the 32 digest bytes and weakens the tamper-resistance
+/// `Sha256` was chosen for. Use `verify_checksum` instead, which compares
+/// the full digest.
+pub fn compute_checksum(tp: ChecksumType, data: &[u8]) -> u64 {
+    match tp {
+        ChecksumType::Crc32c => crc32c::crc32c(data) as u64,
+        ChecksumType::XxHash64 => xxhash_rust::xxh64::xxh64(data, 0),
+        ChecksumType::Sha256 => LittleEndian::read_u64(&Sha256::digest(data)[..8]),
+    }
+}
+
+/// Writes the checksum of `data` under `tp` into `slot`, which must be
+/// exactly `checksum_len(tp)` bytes.
+fn write_checksum(slot: &mut [u8], tp: ChecksumType, data: &[u8]) {
+    match tp {
+        ChecksumType::Crc32c => LittleEndian::write_u32(slot, compute_checksum(tp, data) as u32),
+        ChecksumType::XxHash64 => LittleEndian::write_u64(slot, compute_checksum(tp, data)),
+        ChecksumType::Sha256 => slot.copy_from_slice(&Sha256::digest(data)),
+    }
+}
+
+/// Verifies that `slot` (exactly `checksum_len(tp)` bytes, as written by
+/// `write_checksum`) matches the checksum of `data` under `tp`. Unlike
+/// comparing against `compute_checksum`, this checks the full digest for
+/// `Sha256` rather than just its leading 8 bytes, so it's the right way to
+/// verify a checksum read back off disk.
+pub fn verify_checksum(slot: &[u8], tp: ChecksumType, data: &[u8]) -> bool {
+    match tp {
+        ChecksumType::Crc32c => LittleEndian::read_u32(slot) as u64 == compute_checksum(tp, data),
+        ChecksumType::XxHash64 => LittleEndian::read_u64(slot) == compute_checksum(tp, data),
+        ChecksumType::Sha256 => slot == Sha256::digest(data).as_slice(),
+    }
+}
+
+/// A growable byte buffer that a checksum placeholder can be reserved in and
+/// later filled in. Implemented for both `Vec<u8>` (block/index buffers) and
+/// `BytesMut` (the shared table-level `data_buf`).
+trait ChecksumBuf {
+    fn len(&self) -> usize;
+    fn push_zeros(&mut self, n: usize);
+    fn as_mut_bytes(&mut self) -> &mut [u8];
+}
+
+impl ChecksumBuf for Vec<u8> {
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+    fn push_zeros(&mut self, n: usize) {
+        let new_len = self.len() + n;
+        self.resize(new_len, 0);
+    }
+    fn as_mut_bytes(&mut self) -> &mut [u8] {
+        self.as_mut_slice()
+    }
+}
+
+impl ChecksumBuf for BytesMut {
+    fn len(&self) -> usize {
+        BytesMut::len(self)
+    }
+    fn push_zeros(&mut self, n: usize) {
+        let new_len = self.len() + n;
+        self.resize(new_len, 0);
+    }
+    fn as_mut_bytes(&mut self) -> &mut [u8] {
+        self.as_mut()
+    }
+}
+
+/// Appends a zeroed checksum placeholder of the right width for `tp` and
+/// returns its offset, to be filled in later by `finalize_checksum`.
+fn reserve_checksum<B: ChecksumBuf>(buf: &mut B, tp: ChecksumType) -> usize {
+    let slot_off = buf.len();
+    buf.push_zeros(checksum_len(tp));
+    slot_off
+}
+
+/// Fills in the checksum placeholder reserved at `slot_off` with the checksum
+/// of everything written to `buf` after it.
+fn finalize_checksum<B: ChecksumBuf>(buf: &mut B, slot_off: usize, tp: ChecksumType) {
+    let data_off = slot_off + checksum_len(tp);
+    let (head, tail) = buf.as_mut_bytes().split_at_mut(data_off);
+    write_checksum(&mut head[slot_off..], tp, tail);
+}
+
+/// A table-level encryption key. Only the `key_id` is ever persisted to the
+/// SST (in the properties section); the key bytes stay off disk.
+#[derive(Clone, Copy)]
+pub struct EncryptionKey {
+    pub key_id: u64,
+    pub key: [u8; 32],
+}
+
+/// Encrypts `body` with AES-256-CTR under a fresh random IV, returning
+/// `iv || ciphertext`.
+fn encrypt_block(key: &EncryptionKey, body: &[u8]) -> Vec<u8> {
+    let mut iv = [0u8; IV_LEN];
+    rand::thread_rng().fill_bytes(&mut iv);
+    let mut out = Vec::with_capacity(IV_LEN + body.len());
+    out.extend_from_slice(&iv);
+    out.extend_from_slice(body);
+    let mut cipher = Ctr128BE::<Aes256>::new((&key.key).into(), (&iv).into());
+    cipher.apply_keystream(&mut out[IV_LEN..]);
+    out
+}
+
+/// Reverses `encrypt_block`: splits the leading IV off `iv_and_ciphertext`
+/// and decrypts the rest. This is the reader-side counterpart, for the
+/// `iv || ciphertext` bytes stored for an encrypted block.
+pub fn decrypt_block(key: &EncryptionKey, iv_and_ciphertext: &[u8]) -> Vec<u8> {
+    let (iv, ciphertext) = iv_and_ciphertext.split_at(IV_LEN);
+    let mut out = ciphertext.to_vec();
+    let mut cipher = Ctr128BE::<Aes256>::new((&key.key).into(), iv.into());
+    cipher.apply_keystream(&mut out);
+    out
+}
+
 #[derive(Clone, Copy)]
 pub struct TableBuilderOptions {
     pub block_size: usize,
     pub bloom_fpr: f64,
     pub max_table_size: usize,
+    pub compression_tp: u8,
+    pub encryption_key: Option<EncryptionKey>,
+    pub checksum_tp: ChecksumType,
+    /// `EXTRA_FILTER_TYPE_BINARY_FUSE_8` (default, best for immutable,
+    /// all-keys-known blocks) or `EXTRA_FILTER_TYPE_BLOOM` (robust when fuse
+    /// construction fails on duplicate hashes).
+    pub filter_tp: u8,
 }
 
 impl Default for TableBuilderOptions {
@@ -35,10 +209,89 @@ impl Default for TableBuilderOptions {
             block_size: 64 * 1024,
             bloom_fpr: 0.01,
             max_table_size: 16 * 1024 * 1024,
+            compression_tp: NO_COMPRESSION,
+            encryption_key: None,
+            checksum_tp: ChecksumType::Crc32c,
+            filter_tp: EXTRA_FILTER_TYPE_BINARY_FUSE_8,
         }
     }
 }
 
+/// Compresses `body` with the given algorithm, returning `None` for
+/// `NO_COMPRESSION` or an unrecognized algorithm so the caller can skip the
+/// compression path entirely instead of cloning `body` for nothing.
+fn compress_block(tp: u8, body: &[u8]) -> Option<Vec<u8>> {
+    match tp {
+        SNAPPY_COMPRESSION => Some(
+            snap::raw::Encoder::new()
+                .compress_vec(body)
+                .expect("snappy compression never fails on valid input"),
+        ),
+        LZ4_COMPRESSION => Some(
+            lz4::block::compress(body, None, false)
+                .expect("lz4 compression never fails on valid input"),
+        ),
+        ZSTD_COMPRESSION => {
+            Some(zstd::bulk::compress(body, 0).expect("zstd compression never fails on valid input"))
+        }
+        _ => None,
+    }
+}
+
+/// Reverses `compress_block`: decompresses `payload` under the algorithm
+/// `tag` that was actually used (which may differ from the table's
+/// configured `compression_tp` when a block didn't compress and was stored
+/// raw under `NO_COMPRESSION` instead) into a buffer of `uncompressed_len`
+/// bytes. This is the reader-side counterpart, taking the tag and length
+/// parsed out of a block's record header.
+pub fn decompress_block(tag: u8, uncompressed_len: usize, payload: &[u8]) -> Vec<u8> {
+    match tag {
+        SNAPPY_COMPRESSION => snap::raw::Decoder::new()
+            .decompress_vec(payload)
+            .expect("snappy-compressed block is corrupt"),
+        LZ4_COMPRESSION => lz4::block::decompress(payload, Some(uncompressed_len as i32))
+            .expect("lz4-compressed block is corrupt"),
+        ZSTD_COMPRESSION => {
+            zstd::bulk::decompress(payload, uncompressed_len).expect("zstd-compressed block is corrupt")
+        }
+        _ => payload.to_vec(),
+    }
+}
+
+fn put_uvarint(buf: &mut Vec<u8>, mut v: u64) {
+    loop {
+        let mut b = (v & 0x7f) as u8;
+        v >>= 7;
+        if v != 0 {
+            b |= 0x80;
+        }
+        buf.push(b);
+        if v == 0 {
+            break;
+        }
+    }
+}
+
+/// Reads a uvarint from the start of `buf`, returning the value and the
+/// number of bytes it occupied. Reader-side counterpart of `put_uvarint`,
+/// for parsing the `uncompressed_len` a block's record header was written
+/// with.
+pub fn read_uvarint(buf: &[u8]) -> (u64, usize) {
+    let mut v = 0u64;
+    let mut shift = 0u32;
+    let mut i = 0usize;
+    loop {
+        let b = buf[i];
+        v |= ((b & 0x7f) as u64) << shift;
+        i += 1;
+        if b & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    (v, i)
+}
+
 #[derive(Default)]
 struct EntrySlice {
     buf: Vec<u8>,
@@ -106,7 +359,10 @@ pub struct Builder {
     old_builder: BlockBuilder,
     block_size: usize,
     bloom_fpr: f64,
-    checksum_tp: u8,
+    checksum_tp: ChecksumType,
+    compression_tp: u8,
+    encryption_key: Option<EncryptionKey>,
+    filter_tp: u8,
     key_hashes: Vec<u64>,
     smallest: Vec<u8>,
     biggest: Vec<u8>,
@@ -117,7 +373,10 @@ impl Builder {
         let mut x = Self::default();
         x.fid = fid;
         x.bloom_fpr = opt.bloom_fpr;
-        x.checksum_tp = CRC32_CASTAGNOLI;
+        x.checksum_tp = opt.checksum_tp;
+        x.compression_tp = opt.compression_tp;
+        x.encryption_key = opt.encryption_key;
+        x.filter_tp = opt.filter_tp;
         x.block_size = opt.block_size;
         x
     }
@@ -131,6 +390,18 @@ impl Builder {
         self.biggest.truncate(0);
     }
 
+    /// The block-size cutoff used when deciding to finish a block. When
+    /// encryption is enabled this is `BLOCK_ENCRYPTION_PADDING` smaller than
+    /// `block_size`, leaving room for the IV and compression header added to
+    /// an encrypted block.
+    fn effective_block_size(&self) -> usize {
+        if self.encryption_key.is_some() {
+            self.block_size.saturating_sub(BLOCK_ENCRYPTION_PADDING)
+        } else {
+            self.block_size
+        }
+    }
+
     fn add_property(buf: &mut BytesMut, key: &[u8], val: &[u8]) {
         buf.put_u16_le(key.len() as u16);
         buf.put_slice(key);
@@ -145,11 +416,22 @@ impl Builder {
             self.old_builder.add_entry(key, val);
         } else {
             // Only try to finish block when the key is different than last.
-            if self.block_builder.block.size > self.block_size {
-                self.block_builder.finish_block(self.fid, self.checksum_tp);
+            let cutoff = self.effective_block_size();
+            if self.block_builder.block.size > cutoff {
+                self.block_builder.finish_block(
+                    self.fid,
+                    self.checksum_tp,
+                    self.compression_tp,
+                    self.encryption_key.as_ref(),
+                );
             }
-            if self.old_builder.block.size > self.block_size {
-                self.old_builder.finish_block(self.fid, self.checksum_tp);
+            if self.old_builder.block.size > cutoff {
+                self.old_builder.finish_block(
+                    self.fid,
+                    self.checksum_tp,
+                    self.compression_tp,
+                    self.encryption_key.as_ref(),
+                );
             }
             self.block_builder.add_entry(key, val);
             self.key_hashes.push(farmhash::fingerprint64(key));
@@ -172,10 +454,20 @@ impl Builder {
         if self.block_builder.block.size > 0 {
             let last_key = self.block_builder.block.tmp_keys.get_last();
             self.biggest.extend_from_slice(last_key);
-            self.block_builder.finish_block(self.fid, self.checksum_tp);
+            self.block_builder.finish_block(
+                self.fid,
+                self.checksum_tp,
+                self.compression_tp,
+                self.encryption_key.as_ref(),
+            );
         }
         if self.old_builder.block.size > 0 {
-            self.old_builder.finish_block(self.fid, self.checksum_tp);
+            self.old_builder.finish_block(
+                self.fid,
+                self.checksum_tp,
+                self.compression_tp,
+                self.encryption_key.as_ref(),
+            );
         }
         assert_eq!(self.block_builder.block_keys.length() > 0, true);
         data_buf.extend_from_slice(self.block_builder.buf.as_slice());
@@ -183,12 +475,22 @@ impl Builder {
         data_buf.extend_from_slice(self.old_builder.buf.as_slice());
         let old_data_section_size = self.old_builder.buf.len() as u32;
 
-        self.block_builder
-            .build_index(base_off, self.checksum_tp, &self.key_hashes);
+        self.block_builder.build_index(
+            base_off,
+            self.checksum_tp,
+            &self.key_hashes,
+            self.filter_tp,
+            self.bloom_fpr,
+        );
         data_buf.extend_from_slice(self.block_builder.buf.as_slice());
         let index_section_size = self.block_builder.buf.len() as u32;
-        self.old_builder
-            .build_index(base_off + data_section_size, self.checksum_tp, &[]);
+        self.old_builder.build_index(
+            base_off + data_section_size,
+            self.checksum_tp,
+            &[],
+            self.filter_tp,
+            self.bloom_fpr,
+        );
         data_buf.extend_from_slice(self.old_builder.buf.as_slice());
         let old_index_section_size = self.old_builder.buf.len() as u32;
 
@@ -199,8 +501,15 @@ impl Builder {
         footer.index_offset = footer.old_data_offset + old_data_section_size;
         footer.old_index_offset = footer.index_offset + index_section_size;
         footer.properties_offset = footer.old_index_offset + old_index_section_size;
-        footer.compression_type = NO_COMPRESSION;
-        footer.checksum_type = self.checksum_tp;
+        footer.compression_type = self.compression_tp;
+        footer.checksum_type = self.checksum_tp as u8;
+        if self.encryption_key.is_some() {
+            footer.encryption_type = ENCRYPTION_AES_CTR;
+            footer.iv_len = IV_LEN as u8;
+        } else {
+            footer.encryption_type = ENCRYPTION_NONE;
+            footer.iv_len = 0;
+        }
         footer.table_format_version = TABLE_FORMAT;
         footer.magic = MAGIC_NUMBER;
         data_buf.extend_from_slice(footer.marshal());
@@ -212,14 +521,17 @@ impl Builder {
     }
 
     fn build_properties(&self, buf: &mut BytesMut) {
-        let origin_len = buf.len();
-        buf.put_u32_le(0);
+        let slot_off = reserve_checksum(buf, self.checksum_tp);
         Builder::add_property(buf, PROP_KEY_SMALLEST.as_bytes(), self.smallest.as_slice());
         Builder::add_property(buf, PROP_KEY_BIGGEST.as_bytes(), self.biggest.as_slice());
-        if self.checksum_tp == CRC32_CASTAGNOLI {
-            let checksum = crc32c::crc32c(&buf[(origin_len + 4)..]);
-            LittleEndian::write_u32(&mut buf[origin_len..], checksum);
+        if let Some(key) = &self.encryption_key {
+            Builder::add_property(
+                buf,
+                PROP_KEY_ENCRYPTION_KEY_ID.as_bytes(),
+                &key.key_id.to_le_bytes(),
+            );
         }
+        finalize_checksum(buf, slot_off, self.checksum_tp);
     }
 
     pub fn is_empty(&self) -> bool {
@@ -237,6 +549,13 @@ impl Builder {
 
 pub const FOOTER_SIZE: usize = mem::size_of::<Footer>();
 
+// `Footer` is (de)serialized by raw pointer cast over its whole byte range,
+// so its layout IS the on-disk format: `#[repr(C)]` pins field order and
+// padding to the declaration below, independent of whatever layout the
+// default Rust representation would otherwise choose. Adding a field is a
+// format change — always append it after `magic` so every existing field
+// keeps its offset, and bump `TABLE_FORMAT`.
+#[repr(C)]
 #[derive(Default, Clone, Copy)]
 pub struct Footer {
     pub old_data_offset: u32,
@@ -247,6 +566,8 @@ pub struct Footer {
     pub checksum_type: u8,
     pub table_format_version: u16,
     pub magic: u32,
+    pub encryption_type: u8,
+    pub iv_len: u8,
 }
 
 impl Footer {
@@ -335,11 +656,17 @@ impl BlockBuilder {
         self.block.size += entry_size;
     }
 
-    fn finish_block(&mut self, fid: u64, checksum_tp: u8) {
+    fn finish_block(
+        &mut self,
+        fid: u64,
+        checksum_tp: ChecksumType,
+        compression_tp: u8,
+        encryption_key: Option<&EncryptionKey>,
+    ) {
         self.block_keys.append(self.block.tmp_keys.get_entry(0));
         self.block_addrs
             .push(BlockAddress::new(fid, self.buf.len() as u32));
-        self.buf.put_u32_le(0);
+        let checksum_slot_off = reserve_checksum(&mut self.buf, checksum_tp);
         let begin_off = self.buf.len();
         let num_entries = self.block.tmp_keys.length();
         self.buf.put_u32_le(num_entries as u32);
@@ -356,12 +683,40 @@ impl BlockBuilder {
         for i in 0..num_entries {
             self.build_entry(i, common_prefix_len);
         }
-        let mut checksum = 0u32;
-        if checksum_tp == CRC32_CASTAGNOLI {
-            checksum = crc32c::crc32c(&self.buf[begin_off..]);
+        // The block body is everything written since `begin_off`.
+        let body = self.buf.split_off(begin_off);
+        if compression_tp == NO_COMPRESSION && encryption_key.is_none() {
+            // Neither feature is in use: keep writing the plain block body
+            // with no framing, exactly as before these were added, so the
+            // on-disk layout of a default table is unchanged.
+            self.buf.extend_from_slice(&body);
+        } else {
+            // Compress it in place, independently of every other block, so
+            // random block reads stay possible.
+            let compressed =
+                compress_block(compression_tp, &body).filter(|c| c.len() < body.len());
+            let mut record = Vec::with_capacity(1 + 10 + body.len());
+            match compressed {
+                Some(compressed) => {
+                    record.push(compression_tp);
+                    put_uvarint(&mut record, body.len() as u64);
+                    record.extend_from_slice(&compressed);
+                }
+                None => {
+                    record.push(NO_COMPRESSION);
+                    put_uvarint(&mut record, body.len() as u64);
+                    record.extend_from_slice(&body);
+                }
+            }
+            // Encrypt after compression but before the checksum, so the checksum
+            // validates the ciphertext that actually ends up on disk.
+            if let Some(key) = encryption_key {
+                self.buf.extend_from_slice(&encrypt_block(key, &record));
+            } else {
+                self.buf.extend_from_slice(&record);
+            }
         }
-        let slice = self.buf.as_mut_slice();
-        LittleEndian::write_u32(&mut slice[(begin_off - 4)..], checksum);
+        finalize_checksum(&mut self.buf, checksum_slot_off, checksum_tp);
         self.block.reset()
     }
 
@@ -409,11 +764,17 @@ impl BlockBuilder {
         self.block_addrs.truncate(0);
     }
 
-    fn build_index(&mut self, base_off: u32, checksum_tp: u8, key_hashes: &[u64]) {
+    fn build_index(
+        &mut self,
+        base_off: u32,
+        checksum_tp: ChecksumType,
+        key_hashes: &[u64],
+        filter_tp: u8,
+        bloom_fpr: f64,
+    ) {
         self.buf.truncate(0);
         let num_blocks = self.block_addrs.len();
-        // checksum place holder.
-        self.buf.put_u32_le(0);
+        let checksum_slot_off = reserve_checksum(&mut self.buf, checksum_tp);
         self.buf.put_u32_le(num_blocks as u32);
         let mut common_prefix_len = 0;
         if num_blocks > 0 {
@@ -443,16 +804,17 @@ impl BlockBuilder {
             self.buf.extend_from_slice(&block_key[common_prefix_len..]);
         }
         if key_hashes.len() > 0 {
-            self.build_filter(key_hashes);
+            self.build_filter(key_hashes, filter_tp, bloom_fpr);
         }
         self.buf.push(EXTRA_END);
-        if checksum_tp == CRC32_CASTAGNOLI {
-            let slice = self.buf.as_mut_slice();
-            LittleEndian::write_u32(slice, crc32c::crc32c(&slice[4..]))
-        }
+        finalize_checksum(&mut self.buf, checksum_slot_off, checksum_tp);
     }
 
-    fn build_filter(&mut self, key_hashes: &[u64]) {
+    fn build_filter(&mut self, key_hashes: &[u64], filter_tp: u8, bloom_fpr: f64) {
+        if filter_tp == EXTRA_FILTER_TYPE_BLOOM {
+            self.build_bloom_filter(key_hashes, bloom_fpr);
+            return;
+        }
         if let Ok(filter) = BinaryFuse8::try_from(key_hashes) {
             let bin = bincode::serialize(&filter).unwrap();
             self.buf.push(EXTRA_FILTER);
@@ -460,9 +822,37 @@ impl BlockBuilder {
             self.buf.put_u32_le(bin.len() as u32);
             self.buf.extend_from_slice(&bin);
         } else {
-            warn!("failed to build binary fuse 8 filter");
+            warn!("failed to build binary fuse 8 filter, falling back to bloom filter");
+            self.build_bloom_filter(key_hashes, bloom_fpr);
         }
     }
+
+    /// Builds a classic bloom filter honoring `bloom_fpr`, using double
+    /// hashing over the two halves of each 64-bit key fingerprint.
+    fn build_bloom_filter(&mut self, key_hashes: &[u64], bloom_fpr: f64) {
+        let bits_per_key = (-bloom_fpr.ln() / std::f64::consts::LN_2.powi(2)).ceil() as u64;
+        let nbits = ((key_hashes.len() as u64) * bits_per_key).max(1) as usize;
+        let k = ((bits_per_key as f64) * std::f64::consts::LN_2).round().max(1.0) as u32;
+
+        let mut bits = vec![0u8; (nbits + 7) / 8];
+        for &hash in key_hashes {
+            let h1 = hash as u32;
+            let h2 = (hash >> 32) as u32;
+            for i in 0..k {
+                let idx = ((h1 as u64 + (i as u64) * (h2 as u64)) % (nbits as u64)) as usize;
+                bits[idx / 8] |= 1 << (idx % 8);
+            }
+        }
+
+        self.buf.push(EXTRA_FILTER);
+        self.buf.push(EXTRA_FILTER_TYPE_BLOOM);
+        let mut bin = BytesMut::with_capacity(8 + bits.len());
+        bin.put_u32_le(nbits as u32);
+        bin.put_u32_le(k);
+        bin.extend_from_slice(&bits);
+        self.buf.put_u32_le(bin.len() as u32);
+        self.buf.extend_from_slice(&bin);
+    }
 }
 
 #[derive(Default, Clone, Copy, Debug)]
@@ -499,6 +889,14 @@ fn key_diff_idx(k1: &[u8], k2: &[u8]) -> usize {
     i
 }
 
+// NOTE: there is no table reader in this crate yet, so the round-trip tests
+// below decode a finished block with this module's own
+// decompress_block/decrypt_block/read_uvarint rather than through a
+// production read path. They prove the encoder and decoder agree on the
+// framing `finish_block` writes, not that an actual table reader can parse
+// it. Reader support (Footer.compression_type/encryption_type dispatch,
+// variable-width checksum slots, EXTRA_FILTER_TYPE_BLOOM) is tracked as a
+// follow-up request; this series is write-path only until that lands.
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -512,4 +910,133 @@ mod tests {
         dbg!(es.buf);
         dbg!(es.end_offs);
     }
+
+    fn add_entries(bb: &mut BlockBuilder, num_entries: u32, value: &[u8]) {
+        let val_buf = Value::encode_buf(1, &[1], 1, value);
+        for i in 0..num_entries {
+            let key = format!("key-{:06}", i).into_bytes();
+            bb.add_entry(&key, Value::decode(&val_buf));
+        }
+    }
+
+    /// Parses the `checksum || tag || uvarint(uncompressed_len) || payload`
+    /// layout `finish_block` writes once compression or encryption is
+    /// enabled, and decodes the body back.
+    fn decode_finished_block(buf: &[u8], checksum_tp: ChecksumType) -> Vec<u8> {
+        let record = &buf[checksum_len(checksum_tp)..];
+        let tag = record[0];
+        let (uncompressed_len, n) = read_uvarint(&record[1..]);
+        let payload = &record[1 + n..];
+        decompress_block(tag, uncompressed_len as usize, payload)
+    }
+
+    #[test]
+    fn test_block_compression_round_trip() {
+        for &tp in &[SNAPPY_COMPRESSION, LZ4_COMPRESSION, ZSTD_COMPRESSION] {
+            let mut bb = BlockBuilder::default();
+            // Repetitive keys/values so compression actually has something
+            // to shrink; the round trip must still hold even if it doesn't.
+            add_entries(&mut bb, 40, "x".repeat(64).as_bytes());
+            bb.finish_block(1, ChecksumType::Crc32c, tp, None);
+            let body = decode_finished_block(&bb.buf, ChecksumType::Crc32c);
+            assert_eq!(LittleEndian::read_u32(&body[0..4]), 40);
+        }
+    }
+
+    #[test]
+    fn test_block_encryption_round_trip() {
+        let key = EncryptionKey {
+            key_id: 7,
+            key: [9u8; 32],
+        };
+        let mut bb = BlockBuilder::default();
+        add_entries(&mut bb, 3, "hello".as_bytes());
+        bb.finish_block(1, ChecksumType::Crc32c, NO_COMPRESSION, Some(&key));
+        let decrypted = decrypt_block(&key, &bb.buf[checksum_len(ChecksumType::Crc32c)..]);
+        let tag = decrypted[0];
+        assert_eq!(tag, NO_COMPRESSION);
+        let (uncompressed_len, n) = read_uvarint(&decrypted[1..]);
+        let payload = &decrypted[1 + n..];
+        assert_eq!(payload.len(), uncompressed_len as usize);
+        assert_eq!(LittleEndian::read_u32(&payload[0..4]), 3);
+    }
+
+    #[test]
+    fn test_checksum_algorithms_round_trip() {
+        let data = b"the quick brown fox jumps over the lazy dog";
+        for &tp in &[
+            ChecksumType::Crc32c,
+            ChecksumType::XxHash64,
+            ChecksumType::Sha256,
+        ] {
+            let mut slot = vec![0u8; checksum_len(tp)];
+            write_checksum(&mut slot, tp, data);
+            assert!(verify_checksum(&slot, tp, data));
+            assert!(!verify_checksum(&slot, tp, b"tampered payload"));
+        }
+    }
+
+    #[test]
+    fn test_compute_checksum_sha256_is_truncated() {
+        let data = b"some payload";
+        let mut slot = vec![0u8; checksum_len(ChecksumType::Sha256)];
+        write_checksum(&mut slot, ChecksumType::Sha256, data);
+        // compute_checksum only exposes the leading 8 bytes of the digest;
+        // verify_checksum is the one that checks the full 32.
+        assert_eq!(
+            compute_checksum(ChecksumType::Sha256, data).to_le_bytes()[..],
+            slot[..8]
+        );
+        assert!(verify_checksum(&slot, ChecksumType::Sha256, data));
+    }
+
+    #[test]
+    fn test_bloom_filter_query_and_false_positive_rate() {
+        let mut bb = BlockBuilder::default();
+        let n = 500usize;
+        let keys: Vec<Vec<u8>> = (0..n)
+            .map(|i| format!("bloom-key-{:06}", i).into_bytes())
+            .collect();
+        let hashes: Vec<u64> = keys.iter().map(|k| farmhash::fingerprint64(k)).collect();
+        let fpr = 0.01;
+        bb.build_filter(&hashes, EXTRA_FILTER_TYPE_BLOOM, fpr);
+
+        assert_eq!(bb.buf[0], EXTRA_FILTER);
+        assert_eq!(bb.buf[1], EXTRA_FILTER_TYPE_BLOOM);
+        let bin_len = LittleEndian::read_u32(&bb.buf[2..6]) as usize;
+        let bin = &bb.buf[6..6 + bin_len];
+        let nbits = LittleEndian::read_u32(&bin[0..4]) as usize;
+        let k = LittleEndian::read_u32(&bin[4..8]);
+        let bits = &bin[8..];
+        let contains = |hash: u64| -> bool {
+            let h1 = hash as u32;
+            let h2 = (hash >> 32) as u32;
+            for i in 0..k {
+                let idx = ((h1 as u64 + (i as u64) * (h2 as u64)) % (nbits as u64)) as usize;
+                if bits[idx / 8] & (1 << (idx % 8)) == 0 {
+                    return false;
+                }
+            }
+            true
+        };
+
+        for &hash in &hashes {
+            assert!(contains(hash), "inserted key must never false-negative");
+        }
+
+        let trials = 5000;
+        let false_positives = (0..trials)
+            .filter(|i| {
+                let probe = format!("not-inserted-{:06}", i).into_bytes();
+                contains(farmhash::fingerprint64(&probe))
+            })
+            .count();
+        let observed_fpr = false_positives as f64 / trials as f64;
+        assert!(
+            observed_fpr < fpr * 5.0,
+            "false positive rate {} far exceeds target {}",
+            observed_fpr,
+            fpr
+        );
+    }
 }